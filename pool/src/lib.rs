@@ -0,0 +1,40 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The Grin transaction pool, tasked with handling all unconfirmed
+//! transactions before they get included in a block.
+
+#[macro_use]
+extern crate failure_derive;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+
+pub extern crate grin_core as core;
+pub extern crate grin_keychain as keychain;
+pub extern crate grin_util as util;
+
+mod pool;
+pub mod transaction_pool;
+pub mod types;
+pub mod verifier;
+
+pub use crate::pool::Pool;
+pub use crate::transaction_pool::TransactionPool;
+pub use crate::types::{
+	AncestorRateScoring, BlockChain, DandelionConfig, FeeRateScoring, PoolAdapter, PoolAddResult,
+	PoolConfig, PoolEntry, PoolError, Scoring, TxSource,
+};
+pub use crate::verifier::ContextFreeVerifier;
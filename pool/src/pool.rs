@@ -0,0 +1,474 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The primary transaction pool storage: a flat list of `PoolEntry` plus the
+//! pluggable, package-aware selection logic used when we need to pick a
+//! "mineable" subset of the pool, or decide what to evict under pressure.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use self::core::core::hash::{Hash, Hashed};
+use self::core::core::{Commitment, Committed, Transaction};
+use crate::core;
+use crate::types::{AncestorRateScoring, BlockChain, PoolEntry, PoolError, Scoring, TxSource};
+use crate::util::RwLock;
+
+/// The pool itself, a container for pool entries with the helpers needed to
+/// add to it, and to select a subset of entries to go into a block.
+pub struct Pool<B>
+where
+	B: BlockChain,
+{
+	/// Entries in the pool, in insertion order. Because we never admit a
+	/// child ahead of its parent (see `TransactionPool::add_to_pool`), this
+	/// is also always a valid topological (parents-first) order.
+	pub entries: Vec<PoolEntry>,
+	/// The blockchain this pool is verifying entries against.
+	pub blockchain: Arc<B>,
+	/// Name of this pool, used for logging ("txpool" or "stempool").
+	pub name: String,
+	/// The strategy used to rank packages of entries against each other.
+	pub scoring: Arc<dyn Scoring>,
+	/// The pool's configured capacity (`PoolConfig::max_pool_size` or
+	/// `max_stempool_size`), used as the base for the per-source cap so a
+	/// single source is limited in absolute terms rather than relative to
+	/// however full the pool happens to be right now.
+	capacity: usize,
+	/// The pool's configured weight bound (`PoolConfig::max_pool_weight` or
+	/// `max_stempool_weight`). Once `total_weight()` would exceed this, new
+	/// entries are only admitted if they beat `min_fee_rate()`, and the
+	/// lowest-scoring packages are evicted to make room for them.
+	pub max_weight: u64,
+	/// Strikes recorded against a source identifier, e.g. for submitting a
+	/// tx that failed verification or that a new block double-spent (see
+	/// `reconcile`). Used to down-weight that source's entries under `scoring`
+	/// pressure so they are the first evicted from a full pool.
+	penalties: HashMap<String, u32>,
+}
+
+impl<B> Pool<B>
+where
+	B: BlockChain,
+{
+	/// Construct a new empty pool using the default (ancestor-aware) scoring.
+	pub fn new(chain: Arc<B>, name: String, capacity: usize, max_weight: u64) -> Pool<B> {
+		Pool::new_with_scoring(
+			chain,
+			name,
+			capacity,
+			max_weight,
+			Arc::new(AncestorRateScoring),
+		)
+	}
+
+	/// Construct a new empty pool using the given scoring strategy.
+	pub fn new_with_scoring(
+		chain: Arc<B>,
+		name: String,
+		capacity: usize,
+		max_weight: u64,
+		scoring: Arc<dyn Scoring>,
+	) -> Pool<B> {
+		Pool {
+			entries: Vec::new(),
+			blockchain: chain,
+			name,
+			scoring,
+			capacity,
+			max_weight,
+			penalties: HashMap::new(),
+		}
+	}
+
+	/// Number of entries in the pool.
+	pub fn size(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Is the pool empty?
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// All transactions currently in the pool.
+	pub fn all_transactions(&self) -> Vec<Transaction> {
+		self.entries.iter().map(|x| x.tx.clone()).collect()
+	}
+
+	/// Total weight of all entries currently in the pool.
+	pub fn total_weight(&self) -> u64 {
+		self.entries.iter().map(|x| x.tx.weight() as u64).sum()
+	}
+
+	/// The "dynamic minimum fee rate": the lowest package fee rate among the
+	/// entries currently retained in the pool. A relay floor derived from
+	/// this is what lets us reject clearly-too-cheap incoming txs up front,
+	/// and what callers can advertise to peers. `None` when the pool is
+	/// empty (there is no floor yet).
+	pub fn min_fee_rate(&self) -> Option<u64> {
+		if self.entries.is_empty() {
+			return None;
+		}
+		let commit_index = self.output_commit_index();
+		let remaining: BTreeSet<usize> = (0..self.entries.len()).collect();
+		self.entries
+			.iter()
+			.enumerate()
+			.map(|(i, _)| {
+				let (rate, _) = self
+					.scoring
+					.package(&self.entries, &commit_index, i, &remaining);
+				rate
+			})
+			.min()
+	}
+
+	/// The ancestor package fee rate `entry` would score if it were admitted
+	/// right now, found by scoring it against the pool's existing entries -
+	/// so a low-fee parent already sitting in the pool is folded in with
+	/// this candidate, exactly as it would be once admitted. Used instead of
+	/// `entry.tx.fee_rate()` when comparing against `min_fee_rate()`, so a
+	/// high-fee child can clear the floor on a stuck low-fee parent's
+	/// behalf (see `Scoring::package`).
+	pub fn candidate_rate(&self, entry: &PoolEntry) -> u64 {
+		let mut entries = self.entries.clone();
+		entries.push(entry.clone());
+		let idx = entries.len() - 1;
+		let commit_index = Self::output_commit_index_for(&entries);
+		let remaining: BTreeSet<usize> = (0..entries.len()).collect();
+		let (rate, _) = self.scoring.package(&entries, &commit_index, idx, &remaining);
+		rate
+	}
+
+	/// Number of entries currently in the pool from the given source.
+	pub fn source_count(&self, source: &TxSource) -> usize {
+		self.entries
+			.iter()
+			.filter(|x| x.src.identifier == source.identifier)
+			.count()
+	}
+
+	/// The maximum number of entries a single source may hold in this pool,
+	/// given `max_share_per_source` of the pool's configured capacity.
+	/// Always at least 1, so a source is never locked out entirely.
+	pub fn source_cap(&self, max_share_per_source: f32) -> usize {
+		((self.capacity as f32 * max_share_per_source) as usize).max(1)
+	}
+
+	/// Record a strike against `source`, e.g. because one of its
+	/// transactions failed verification or was double-spent by a new block.
+	/// Strikes accumulate and are never reset; they only matter in
+	/// relative terms against other sources.
+	pub fn penalize(&mut self, source: &TxSource, amount: u32) {
+		*self.penalties.entry(source.identifier.clone()).or_insert(0) += amount;
+	}
+
+	/// Current penalty recorded against `source`.
+	pub fn penalty(&self, source: &TxSource) -> u32 {
+		self.penalties
+			.get(&source.identifier)
+			.copied()
+			.unwrap_or(0)
+	}
+
+	/// Index of the lowest-scoring entry in the pool that came from `source`,
+	/// if any. Used to make room for a new entry under the per-source cap.
+	pub fn lowest_scoring_index_for_source(&self, source: &TxSource) -> Option<usize> {
+		let commit_index = self.output_commit_index();
+		let remaining: BTreeSet<usize> = (0..self.entries.len()).collect();
+		self.entries
+			.iter()
+			.enumerate()
+			.filter(|(_, e)| e.src.identifier == source.identifier)
+			.map(|(i, _)| {
+				let (rate, _) = self
+					.scoring
+					.package(&self.entries, &commit_index, i, &remaining);
+				let penalty = self.penalty(&self.entries[i].src);
+				(i, self.effective_rate(rate, &penalty))
+			})
+			.min_by_key(|(_, rate)| *rate)
+			.map(|(i, _)| i)
+	}
+
+	/// The penalty-adjusted ancestor package rate of the entry currently at
+	/// `idx`, i.e. the same metric `lowest_scoring_index_for_source` picked
+	/// it by. Callers deciding whether to displace that entry should compare
+	/// against this rather than its raw `tx.fee_rate()`, or the comparison
+	/// is measuring two different things.
+	pub fn effective_rate_of(&self, idx: usize) -> u64 {
+		let commit_index = self.output_commit_index();
+		let remaining: BTreeSet<usize> = (0..self.entries.len()).collect();
+		let (rate, _) = self
+			.scoring
+			.package(&self.entries, &commit_index, idx, &remaining);
+		let penalty = self.penalty(&self.entries[idx].src);
+		self.effective_rate(rate, &penalty)
+	}
+
+	/// The penalty-adjusted ancestor package rate `entry` would score if
+	/// admitted right now (see `candidate_rate`), using the penalty already
+	/// recorded against its own source. The counterpart to
+	/// `effective_rate_of` for comparing a not-yet-admitted candidate
+	/// against an existing entry on equal terms.
+	pub fn effective_candidate_rate(&self, entry: &PoolEntry) -> u64 {
+		let rate = self.candidate_rate(entry);
+		let penalty = self.penalty(&entry.src);
+		self.effective_rate(rate, &penalty)
+	}
+
+	/// Remove the entry at `idx` and return it.
+	pub fn remove_entry(&mut self, idx: usize) -> PoolEntry {
+		self.entries.remove(idx)
+	}
+
+	/// Remove the entries at the given indices from the pool, preserving the
+	/// relative order of the remaining entries.
+	fn remove_by_index(&mut self, indices: &BTreeSet<usize>) {
+		let mut i = 0;
+		self.entries.retain(|_| {
+			let keep = !indices.contains(&i);
+			i += 1;
+			keep
+		});
+	}
+
+	/// Build a map of output commitment -> index into `self.entries` for the
+	/// tx that produced it. Used by ancestor-aware scoring to walk in-pool
+	/// ancestor chains.
+	fn output_commit_index(&self) -> HashMap<Commitment, usize> {
+		Self::output_commit_index_for(&self.entries)
+	}
+
+	/// As `output_commit_index`, but over an arbitrary entry slice rather
+	/// than `self.entries` - used by `candidate_rate` to score a tx that
+	/// isn't in the pool yet alongside the entries that are.
+	fn output_commit_index_for(entries: &[PoolEntry]) -> HashMap<Commitment, usize> {
+		let mut index = HashMap::new();
+		for (i, entry) in entries.iter().enumerate() {
+			for commit in entry.tx.outputs_committed() {
+				index.insert(commit, i);
+			}
+		}
+		index
+	}
+
+	/// Build a map of input commitment -> indices of the entries that spend
+	/// it, i.e. the in-pool children of whichever entry produced that
+	/// output. Used to find the full descendant set of an entry for
+	/// eviction, mirroring `output_commit_index`'s use for ancestors.
+	fn input_commit_index(&self) -> HashMap<Commitment, Vec<usize>> {
+		let mut index: HashMap<Commitment, Vec<usize>> = HashMap::new();
+		for (i, entry) in self.entries.iter().enumerate() {
+			for commit in entry.tx.inputs_committed() {
+				index.entry(commit).or_insert_with(Vec::new).push(i);
+			}
+		}
+		index
+	}
+
+	/// The full set of in-pool descendants of `idx` (not including `idx`
+	/// itself), found by following each output forward to whichever entries
+	/// spend it, transitively.
+	fn descendant_indices(
+		&self,
+		idx: usize,
+		input_index: &HashMap<Commitment, Vec<usize>>,
+	) -> BTreeSet<usize> {
+		let mut descendants = BTreeSet::new();
+		let mut stack = vec![idx];
+		while let Some(i) = stack.pop() {
+			for commit in self.entries[i].tx.outputs_committed() {
+				if let Some(children) = input_index.get(&commit) {
+					for &child in children {
+						if child != idx && descendants.insert(child) {
+							stack.push(child);
+						}
+					}
+				}
+			}
+		}
+		descendants
+	}
+
+	/// The "descendant package" rooted at `idx`: the entry plus every entry
+	/// that (transitively) depends on it. Evicting an entry without also
+	/// evicting its descendants would leave them spending an output that no
+	/// longer exists anywhere, so eviction always removes this whole set
+	/// together. Scored by penalty-adjusted rate - using the worst (highest)
+	/// penalty recorded across the package's sources, as `select_valid_transactions`
+	/// does - so a penalized source's entries actually are the first evicted
+	/// under pool pressure, not just under the narrower per-source cap.
+	fn descendant_package(
+		&self,
+		idx: usize,
+		input_index: &HashMap<Commitment, Vec<usize>>,
+	) -> (u64, BTreeSet<usize>) {
+		let mut package = self.descendant_indices(idx, input_index);
+		package.insert(idx);
+
+		let (fee, weight) = package.iter().fold((0u64, 0u64), |(fee, weight), &i| {
+			let tx = &self.entries[i].tx;
+			(fee + tx.fee(), weight + tx.weight() as u64)
+		});
+		let raw_rate = if weight == 0 { 0 } else { fee / weight };
+		let penalty = package
+			.iter()
+			.map(|&i| self.penalty(&self.entries[i].src))
+			.max()
+			.unwrap_or(0);
+		(self.effective_rate(raw_rate, &penalty), package)
+	}
+
+	/// Evict whole descendant packages, lowest-rate first, until the pool's
+	/// total weight is back at or under `self.max_weight`. Never evicts a
+	/// parent while leaving one of its children behind: each eviction takes
+	/// out the entry and everything that depends on it in one go. Returns the
+	/// hashes of every evicted tx, so callers can drop them from any cache
+	/// keyed on "is this tx still in a pool" (see `ContextFreeVerifier::forget`).
+	pub fn evict_to_fit(&mut self) -> Vec<Hash> {
+		let mut evicted = Vec::new();
+		while self.total_weight() > self.max_weight {
+			let input_index = self.input_commit_index();
+			let worst = (0..self.entries.len())
+				.map(|idx| self.descendant_package(idx, &input_index))
+				.min_by_key(|(rate, _)| *rate);
+
+			match worst {
+				Some((_, package)) => {
+					evicted.extend(package.iter().map(|&i| self.entries[i].tx.hash()));
+					self.remove_by_index(&package);
+				}
+				None => break,
+			}
+		}
+		evicted
+	}
+
+	/// Scale down a raw package rate by the penalties recorded against the
+	/// sources that contributed to it, so repeatedly-penalized sources sink
+	/// to the bottom of the ranking.
+	fn effective_rate(&self, raw_rate: u64, penalty: &u32) -> u64 {
+		raw_rate / (1 + u64::from(*penalty))
+	}
+
+	/// Select entries to mine, scoring each remaining entry's package via
+	/// `self.scoring` (by default child-pays-for-parent ancestor rate) and
+	/// greedily taking the best-scoring package that still fits.
+	///
+	/// We recompute remaining scores after each pick since ancestors just
+	/// claimed by one package must not be counted again for another. A
+	/// package that would overflow `max_weight` is skipped entirely (never
+	/// split) and we move on to the next best package.
+	pub fn select_valid_transactions(&self, max_weight: u64) -> Result<Vec<Transaction>, PoolError> {
+		let commit_index = self.output_commit_index();
+		let mut remaining: BTreeSet<usize> = (0..self.entries.len()).collect();
+		let mut included: BTreeSet<usize> = BTreeSet::new();
+		let mut total_weight = 0u64;
+
+		while !remaining.is_empty() {
+			let mut best: Option<(usize, u64, BTreeSet<usize>)> = None;
+			for &idx in remaining.iter() {
+				let (raw_rate, package) = self
+					.scoring
+					.package(&self.entries, &commit_index, idx, &remaining);
+				let penalty = package
+					.iter()
+					.map(|&i| self.penalty(&self.entries[i].src))
+					.max()
+					.unwrap_or(0);
+				let rate = self.effective_rate(raw_rate, &penalty);
+				if best
+					.as_ref()
+					.map_or(true, |(_, best_rate, _)| rate > *best_rate)
+				{
+					best = Some((idx, rate, package));
+				}
+			}
+
+			// Safe to unwrap, `remaining` is non-empty so we always find a best.
+			let (idx, _, package) = best.unwrap();
+			let package_weight: u64 = package
+				.iter()
+				.map(|&i| self.entries[i].tx.weight() as u64)
+				.sum();
+
+			if total_weight + package_weight > max_weight {
+				// Doesn't fit as a whole package - do not split it, just drop
+				// this candidate and let its ancestors be reconsidered on
+				// their own (or as part of some other package) next time
+				// round.
+				remaining.remove(&idx);
+				continue;
+			}
+
+			total_weight += package_weight;
+			for i in &package {
+				remaining.remove(i);
+				included.insert(*i);
+			}
+		}
+
+		// `included` is a `BTreeSet` so this is already parents-before-children.
+		Ok(included
+			.into_iter()
+			.map(|i| self.entries[i].tx.clone())
+			.collect())
+	}
+
+	/// Remove all entries reconciled into a new block, along with any entry
+	/// left behind that now double-spends against the block. A tx removed
+	/// for double-spending rather than for being mined counts as a strike of
+	/// `penalty_per_strike` against its source - a conflicting spend of the
+	/// same inputs made it into the block instead (`PoolConfig::penalty_per_strike`).
+	/// Returns the hashes of every entry removed, mined or double-spent
+	/// alike, so callers can drop them from any cache keyed on "is this tx
+	/// still in a pool" (see `ContextFreeVerifier::forget`).
+	pub fn reconcile(
+		&mut self,
+		block: &self::core::core::Block,
+		penalty_per_strike: u32,
+	) -> Result<Vec<Hash>, PoolError> {
+		let in_block: BTreeSet<_> = block.inputs_committed().into_iter().collect();
+		let block_kernels: BTreeSet<_> = block.kernels().iter().map(|k| k.excess()).collect();
+
+		let mut to_remove = BTreeSet::new();
+		let mut to_penalize = Vec::new();
+		for (i, entry) in self.entries.iter().enumerate() {
+			let in_this_block = entry
+				.tx
+				.kernels()
+				.iter()
+				.all(|k| block_kernels.contains(&k.excess()));
+			let conflicts = entry
+				.tx
+				.inputs_committed()
+				.into_iter()
+				.any(|c| in_block.contains(&c));
+			if in_this_block || conflicts {
+				to_remove.insert(i);
+			}
+			if conflicts && !in_this_block {
+				to_penalize.push(entry.src.clone());
+			}
+		}
+		let removed: Vec<Hash> = to_remove.iter().map(|&i| self.entries[i].tx.hash()).collect();
+		self.remove_by_index(&to_remove);
+		for source in to_penalize {
+			self.penalize(&source, penalty_per_strike);
+		}
+		Ok(removed)
+	}
+}
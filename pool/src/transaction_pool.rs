@@ -0,0 +1,422 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The top level public API for interacting with the transaction pool.
+//! Wraps the lower level `txpool`/`stempool` each as a `Pool` and presents
+//! the single `add_to_pool` / `prepare_mineable_transactions` /
+//! `reconcile_block` surface the rest of the node talks to.
+
+use std::sync::Arc;
+
+use self::core::core::hash::{Hash, Hashed};
+use self::core::core::verifier_cache::VerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::global;
+use crate::core;
+use crate::pool::Pool;
+use crate::types::{BlockChain, PoolAdapter, PoolAddResult, PoolConfig, PoolError, TxSource};
+use crate::util::RwLock;
+use crate::verifier::ContextFreeVerifier;
+
+/// Transaction pool implementation.
+pub struct TransactionPool<B, P>
+where
+	B: BlockChain,
+	P: PoolAdapter,
+{
+	/// Pool Config
+	pub config: PoolConfig,
+	/// Our transaction pool.
+	pub txpool: Pool<B>,
+	/// Our Dandelion "stem" pool.
+	pub stempool: Pool<B>,
+	/// Transactions that have passed context-free verification but whose
+	/// time-relative conditions (absolute kernel lock height, NRD relative
+	/// lock-height kernel) are not yet satisfied at the current tip.
+	/// Re-checked and, once satisfied, moved into `txpool` automatically as
+	/// the tip advances - see `reconcile_block`.
+	pub pending: Pool<B>,
+	/// The blockchain
+	pub blockchain: Arc<B>,
+	/// Pool adapter, used to relay accepted transactions to the rest of the node.
+	pub adapter: Arc<P>,
+	/// Cache of previously verified rangeproofs and kernel signatures, shared
+	/// with the rest of the node so we never re-verify the same tx twice.
+	pub verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	/// Caches which tx hashes have already passed context-free verification,
+	/// so re-checking the pool against a new tip never repeats work whose
+	/// answer can't have changed.
+	context_free: ContextFreeVerifier,
+}
+
+impl<B, P> TransactionPool<B, P>
+where
+	B: BlockChain,
+	P: PoolAdapter,
+{
+	/// Create a new transaction pool.
+	pub fn new(
+		config: PoolConfig,
+		chain: Arc<B>,
+		adapter: Arc<P>,
+		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	) -> TransactionPool<B, P> {
+		TransactionPool {
+			txpool: Pool::new(
+				chain.clone(),
+				"txpool".to_string(),
+				config.max_pool_size,
+				config.max_pool_weight,
+			),
+			stempool: Pool::new(
+				chain.clone(),
+				"stempool".to_string(),
+				config.max_stempool_size,
+				config.max_stempool_weight,
+			),
+			pending: Pool::new(
+				chain.clone(),
+				"pending".to_string(),
+				config.max_pool_size,
+				config.max_pool_weight,
+			),
+			config,
+			blockchain: chain,
+			adapter,
+			verifier_cache,
+			context_free: ContextFreeVerifier::new(),
+		}
+	}
+
+	/// Number of transactions in the txpool.
+	pub fn total_size(&self) -> usize {
+		self.txpool.size()
+	}
+
+	/// Number of transactions parked pending a time-relative condition.
+	pub fn pending_size(&self) -> usize {
+		self.pending.size()
+	}
+
+	/// The txpool's current dynamic minimum fee rate: the floor a new tx's
+	/// ancestor fee rate must clear once the pool is at its weight bound.
+	/// Node code can advertise this to peers as the relay floor. `None`
+	/// while the txpool is empty.
+	pub fn min_fee_rate(&self) -> Option<u64> {
+		self.txpool.min_fee_rate()
+	}
+
+	/// Add the given transaction to the pool. Goes to the stempool if
+	/// `stem` is set, otherwise the public txpool.
+	///
+	/// Verification happens in two stages. First, context-free checks
+	/// (rangeproofs, kernel signatures, weight) that can never be
+	/// invalidated by the tip moving - these are cached per tx hash by
+	/// `self.context_free` and never repeated for the same tx - followed by
+	/// coinbase maturity and UTXO validity, which are not cached and run on
+	/// every call. Second, the time-relative check (`BlockChain::is_tx_time_valid`)
+	/// - if `tx`'s absolute lock height or an NRD relative lock-height
+	/// kernel isn't satisfied yet, it is parked in `self.pending`
+	/// (`PoolAddResult::Pending`) rather than rejected, and promoted
+	/// automatically once the tip catches up (see `reconcile_block`).
+	/// `pending` is bounded and capped per-source exactly like
+	/// `txpool`/`stempool` below, so an attacker can't grow it without bound
+	/// by submitting cheap txs with far-future lock heights.
+	///
+	/// Once the target pool is at its configured weight bound
+	/// (`PoolConfig::max_pool_weight`/`max_stempool_weight`), an entry is
+	/// only admitted if its ancestor package fee rate beats
+	/// `Pool::min_fee_rate()` (`PoolAddResult::RejectedLowScore` otherwise),
+	/// and admitting it evicts whichever lowest-scoring packages are needed
+	/// to make room. If that eviction takes out the new entry itself, the
+	/// outcome is also `PoolAddResult::RejectedLowScore`.
+	///
+	/// A source that already occupies its configured `max_share_per_source`
+	/// of the target pool only gets in by out-scoring its own
+	/// lowest-scoring entry there, which is evicted to make room
+	/// (`PoolAddResult::Replaced`); otherwise the entry is turned away
+	/// (`PoolAddResult::RejectedSourceCapExceeded`) without disturbing
+	/// anyone else's entries. A source whose tx fails verification here
+	/// earns a strike (see `Pool::penalize`) so it is the first evicted the
+	/// next time the pool is under real pressure.
+	pub fn add_to_pool(
+		&mut self,
+		src: TxSource,
+		tx: Transaction,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<PoolAddResult, PoolError> {
+		let fee_rate = tx.fee_rate();
+		if fee_rate < self.config.accept_fee_base {
+			return Err(PoolError::LowFeeTransaction(fee_rate));
+		}
+
+		if let Err(e) = self
+			.context_free
+			.verify(&tx, self.verifier_cache.clone())
+			.and_then(|_| self.blockchain.verify_coinbase_maturity(&tx))
+			.and_then(|_| self.blockchain.validate_tx(&tx))
+		{
+			let pool = if stem {
+				&mut self.stempool
+			} else {
+				&mut self.txpool
+			};
+			pool.penalize(&src, self.config.penalty_per_strike);
+			// It may have passed the context-free stage before failing here.
+			self.context_free.forget(&tx.hash());
+			return Err(e);
+		}
+
+		let entry = crate::types::PoolEntry::new(src.clone(), tx);
+		let tx_hash = entry.tx.hash();
+
+		if !self
+			.blockchain
+			.is_tx_time_valid(&entry.tx, header.height)?
+		{
+			// Still subject to the same weight bound and per-source cap as
+			// txpool/stempool, otherwise `pending` is an unbounded backdoor
+			// around the capacity limits those enforce.
+			let (outcome, forgotten) = Self::admit(
+				&mut self.pending,
+				self.config.max_share_per_source,
+				&src,
+				entry,
+			);
+			for hash in &forgotten {
+				self.context_free.forget(hash);
+			}
+			return match outcome {
+				PoolAddResult::RejectedLowScore(r) => {
+					self.context_free.forget(&tx_hash);
+					Ok(PoolAddResult::RejectedLowScore(r))
+				}
+				PoolAddResult::RejectedSourceCapExceeded => {
+					self.context_free.forget(&tx_hash);
+					Ok(PoolAddResult::RejectedSourceCapExceeded)
+				}
+				_ => Ok(PoolAddResult::Pending),
+			};
+		}
+
+		let pool = if stem {
+			&mut self.stempool
+		} else {
+			&mut self.txpool
+		};
+
+		let (result, forgotten) =
+			Self::admit(pool, self.config.max_share_per_source, &src, entry.clone());
+		for hash in &forgotten {
+			self.context_free.forget(hash);
+		}
+
+		match result {
+			PoolAddResult::RejectedLowScore(_) | PoolAddResult::RejectedSourceCapExceeded => {
+				self.context_free.forget(&tx_hash);
+				Ok(result)
+			}
+			_ => {
+				if stem {
+					self.adapter.stem_tx_accepted(&entry)?;
+				} else {
+					self.adapter.tx_accepted(&entry);
+				}
+				Ok(result)
+			}
+		}
+	}
+
+	/// Attempt to admit `entry` from `src` into `pool`, enforcing the weight
+	/// bound and per-source cap the same way regardless of which underlying
+	/// pool (`txpool`, `stempool` or `pending`) it targets, and evicting to
+	/// make room if admitting it pushed the pool over its bound. Alongside
+	/// the outcome, returns the hashes of every tx that left the pool for
+	/// good in the process (a replaced entry, anything `evict_to_fit` took
+	/// out) - callers use this to keep `context_free` from caching hashes for
+	/// txs no longer sitting in any pool (see `ContextFreeVerifier::forget`).
+	fn admit(
+		pool: &mut Pool<B>,
+		max_share_per_source: f32,
+		src: &TxSource,
+		entry: crate::types::PoolEntry,
+	) -> (PoolAddResult, Vec<Hash>) {
+		// The pool is already at its weight bound: only let this entry in if
+		// it out-earns the cheapest package we are currently holding onto.
+		// Scored as the ancestor package it would form once admitted, not its
+		// own isolated fee rate, so a high-fee child can clear the floor on
+		// a stuck low-fee parent's behalf.
+		let entry_hash = entry.tx.hash();
+		let mut candidate_rate = 0;
+		if pool.total_weight() + entry.tx.weight() as u64 > pool.max_weight {
+			candidate_rate = pool.candidate_rate(&entry);
+			if let Some(floor) = pool.min_fee_rate() {
+				if candidate_rate <= floor {
+					return (PoolAddResult::RejectedLowScore(candidate_rate), Vec::new());
+				}
+			}
+		}
+
+		let mut forgotten = Vec::new();
+		let result = if pool.source_count(src) + 1 > pool.source_cap(max_share_per_source) {
+			match pool.lowest_scoring_index_for_source(src) {
+				// Same metric `lowest_scoring_index_for_source` picked `idx`
+				// by - penalty-adjusted ancestor package rate - on both
+				// sides, so penalization actually has a say in which entry
+				// gets displaced.
+				Some(idx)
+					if pool.effective_candidate_rate(&entry) > pool.effective_rate_of(idx) =>
+				{
+					let replaced = pool.remove_entry(idx);
+					forgotten.push(replaced.tx.hash());
+					pool.entries.push(entry);
+					PoolAddResult::Replaced(Box::new(replaced.tx))
+				}
+				_ => PoolAddResult::RejectedSourceCapExceeded,
+			}
+		} else {
+			pool.entries.push(entry);
+			PoolAddResult::Accepted
+		};
+
+		// Make room if admitting this entry pushed us over the bound. Beating
+		// the floor above does not guarantee the new entry survives this:
+		// eviction ranks by penalty-adjusted descendant package rate, so the
+		// entry itself can be the one evicted, and is then reported as not
+		// admitted.
+		forgotten.extend(pool.evict_to_fit());
+		let result = if forgotten.contains(&entry_hash) {
+			PoolAddResult::RejectedLowScore(candidate_rate)
+		} else {
+			result
+		};
+
+		(result, forgotten)
+	}
+
+	/// Select a set of mineable transactions for block building.
+	///
+	/// Scores in-pool packages (a transaction plus its unconfirmed
+	/// ancestors) by ancestor fee rate rather than each transaction's own
+	/// fee rate, so a low-fee parent is carried into the block by a
+	/// high-fee child (child-pays-for-parent). See `Pool::select_valid_transactions`.
+	pub fn prepare_mineable_transactions(&self) -> Result<Vec<Transaction>, PoolError> {
+		let max_weight = global::max_block_weight() as u64;
+		self.txpool.select_valid_transactions(max_weight)
+	}
+
+	/// Reconcile the transaction pool against the given block, removing any
+	/// transaction that made it into the block or now double-spends against
+	/// it. Since `min_fee_rate()` is always computed from the entries
+	/// currently held, the dynamic floor drops on its own as soon as this
+	/// frees up space. `pending` is reconciled the same way, so a tx parked
+	/// there that gets double-spent by the block never sits around waiting
+	/// to wrongly mature.
+	///
+	/// Also re-targets every entry's time-relative condition against the
+	/// block's height: anything in `pending` that has now matured is moved
+	/// into `txpool` without being resubmitted, and - covering a reorg that
+	/// moves the tip backwards - anything in `txpool`/`stempool` whose
+	/// condition no longer holds is parked back into `pending`.
+	pub fn reconcile_block(&mut self, block: &Block) -> Result<(), PoolError> {
+		let removed_tx = self.txpool.reconcile(block, self.config.penalty_per_strike)?;
+		let removed_stem = self
+			.stempool
+			.reconcile(block, self.config.penalty_per_strike)?;
+		let removed_pending = self
+			.pending
+			.reconcile(block, self.config.penalty_per_strike)?;
+		for hash in removed_tx
+			.iter()
+			.chain(removed_stem.iter())
+			.chain(removed_pending.iter())
+		{
+			self.context_free.forget(hash);
+		}
+		self.retarget_time_relative(block.header.height)?;
+		Ok(())
+	}
+
+	/// Move every `txpool`/`stempool` entry whose time-relative condition no
+	/// longer holds at `height` into `pending`, then promote every `pending`
+	/// entry whose condition now holds into `txpool`.
+	fn retarget_time_relative(&mut self, height: u64) -> Result<(), PoolError> {
+		self.park_immature(height, false)?;
+		self.park_immature(height, true)?;
+		self.promote_matured(height)?;
+		Ok(())
+	}
+
+	/// Park any entry of the given pool whose time-relative condition no
+	/// longer holds at `height` - only reachable via a reorg, since the tip
+	/// only ever moves forward otherwise.
+	fn park_immature(&mut self, height: u64, stem: bool) -> Result<(), PoolError> {
+		let pool = if stem {
+			&mut self.stempool
+		} else {
+			&mut self.txpool
+		};
+
+		let mut parked = Vec::new();
+		let mut i = 0;
+		while i < pool.entries.len() {
+			if self.blockchain.is_tx_time_valid(&pool.entries[i].tx, height)? {
+				i += 1;
+			} else {
+				parked.push(pool.remove_entry(i));
+			}
+		}
+		self.pending.entries.extend(parked);
+		for hash in self.pending.evict_to_fit() {
+			self.context_free.forget(&hash);
+		}
+		Ok(())
+	}
+
+	/// Move every `pending` entry whose time-relative condition now holds at
+	/// `height` into `txpool`, evicting to fit if that pushes it over its
+	/// weight bound. Re-validates each one against the current UTXO set
+	/// first and drops any that no longer pass - it may have sat in
+	/// `pending` long enough for one of its inputs to be spent by something
+	/// else entirely.
+	fn promote_matured(&mut self, height: u64) -> Result<(), PoolError> {
+		let mut matured = Vec::new();
+		let mut i = 0;
+		while i < self.pending.entries.len() {
+			if self
+				.blockchain
+				.is_tx_time_valid(&self.pending.entries[i].tx, height)?
+			{
+				matured.push(self.pending.remove_entry(i));
+			} else {
+				i += 1;
+			}
+		}
+		if matured.is_empty() {
+			return Ok(());
+		}
+		let (valid, invalid): (Vec<_>, Vec<_>) = matured
+			.into_iter()
+			.partition(|entry| self.blockchain.validate_tx(&entry.tx).is_ok());
+		for entry in &invalid {
+			self.context_free.forget(&entry.tx.hash());
+		}
+		self.txpool.entries.extend(valid);
+		for hash in self.txpool.evict_to_fit() {
+			self.context_free.forget(&hash);
+		}
+		Ok(())
+	}
+}
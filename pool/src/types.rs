@@ -0,0 +1,367 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Types for the transaction pool's top level public API.
+
+use std::collections::{BTreeSet, HashMap};
+
+use self::core::core::hash::Hash;
+use self::core::core::{BlockHeader, Commitment, Committed, Transaction};
+use crate::core;
+
+/// Where a transaction originated from, for logging, scoring and anti-spam
+/// purposes. Two transactions from the same peer/relay share an
+/// `identifier` so we can cap how much of the pool a single source occupies
+/// and penalize a source that misbehaves.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TxSource {
+	/// Human readable string for logging, e.g. "broadcast" or "stem".
+	pub debug_name: String,
+	/// Unique identifier for the source of this tx, e.g. a peer address or
+	/// "api" for locally submitted txs. Used to key per-source limits.
+	pub identifier: String,
+}
+
+/// Represents a single entry in the pool.
+/// A single parsed transaction, along with the per-entry metadata we track
+/// while it sits in the pool.
+#[derive(Clone, Debug)]
+pub struct PoolEntry {
+	/// Where this tx originated from.
+	pub src: TxSource,
+	/// The transaction itself.
+	pub tx: Transaction,
+}
+
+impl PoolEntry {
+	/// Construct a new pool entry around the provided transaction.
+	pub fn new(src: TxSource, tx: Transaction) -> PoolEntry {
+		PoolEntry { src, tx }
+	}
+}
+
+/// A strategy for ranking pool entries against each other, used to decide
+/// what goes into the next block (`prepare_mineable_transactions`), the
+/// bounded pool's admission floor (`Pool::min_fee_rate`) and which of a
+/// source's own entries is displaced under the per-source cap. Eviction to
+/// fit the weight bound (`Pool::evict_to_fit`) does not use it: it ranks
+/// descendant packages by their own combined fee rate.
+///
+/// Implementations score the *package* rooted at a given entry - the entry
+/// plus whichever of its in-pool ancestors are still present in `remaining`
+/// - so that a scoring strategy can choose to fold a cheap parent's fee in
+/// with its child's (see `AncestorRateScoring`), or ignore ancestors
+/// entirely and just look at the entry itself (see `FeeRateScoring`).
+pub trait Scoring: Send + Sync {
+	/// Score the package rooted at `entries[idx]`, restricted to ancestors
+	/// still present in `remaining`. Returns the package's fee rate and the
+	/// set of entry indices (including `idx`) that make up the package.
+	fn package(
+		&self,
+		entries: &[PoolEntry],
+		commit_index: &HashMap<Commitment, usize>,
+		idx: usize,
+		remaining: &BTreeSet<usize>,
+	) -> (u64, BTreeSet<usize>);
+}
+
+/// Fee-rate-only scoring: each entry is scored purely on its own fee rate,
+/// with no regard for in-pool ancestors or descendants. Not the default -
+/// `Pool::new` uses `AncestorRateScoring`; pass this to
+/// `Pool::new_with_scoring` to use it.
+#[derive(Clone, Debug, Default)]
+pub struct FeeRateScoring;
+
+impl Scoring for FeeRateScoring {
+	fn package(
+		&self,
+		entries: &[PoolEntry],
+		_commit_index: &HashMap<Commitment, usize>,
+		idx: usize,
+		_remaining: &BTreeSet<usize>,
+	) -> (u64, BTreeSet<usize>) {
+		let mut package = BTreeSet::new();
+		package.insert(idx);
+		(entries[idx].tx.fee_rate(), package)
+	}
+}
+
+/// Ancestor-package (child-pays-for-parent) scoring: an entry's score is the
+/// combined fee rate of the entry plus all of its in-pool ancestors, so a
+/// cheap parent with an expensive child is carried into the block by that
+/// child. This is the scoring used to fix the selection behaviour described
+/// in the module's ancestor-aware selection work.
+#[derive(Clone, Debug, Default)]
+pub struct AncestorRateScoring;
+
+impl AncestorRateScoring {
+	/// The full set of in-pool ancestors of `idx` (not including `idx`
+	/// itself), found by following each input back to the entry that
+	/// produced the output it spends.
+	fn ancestor_indices(
+		entries: &[PoolEntry],
+		idx: usize,
+		commit_index: &HashMap<Commitment, usize>,
+	) -> BTreeSet<usize> {
+		let mut ancestors = BTreeSet::new();
+		let mut stack = vec![idx];
+		while let Some(i) = stack.pop() {
+			for commit in entries[i].tx.inputs_committed() {
+				if let Some(&parent) = commit_index.get(&commit) {
+					if parent != idx && ancestors.insert(parent) {
+						stack.push(parent);
+					}
+				}
+			}
+		}
+		ancestors
+	}
+}
+
+impl Scoring for AncestorRateScoring {
+	fn package(
+		&self,
+		entries: &[PoolEntry],
+		commit_index: &HashMap<Commitment, usize>,
+		idx: usize,
+		remaining: &BTreeSet<usize>,
+	) -> (u64, BTreeSet<usize>) {
+		let mut package: BTreeSet<usize> = Self::ancestor_indices(entries, idx, commit_index)
+			.into_iter()
+			.filter(|i| remaining.contains(i))
+			.collect();
+		package.insert(idx);
+
+		let (fee, weight) = package.iter().fold((0u64, 0u64), |(fee, weight), &i| {
+			let tx = &entries[i].tx;
+			(fee + tx.fee(), weight + tx.weight() as u64)
+		});
+		let rate = if weight == 0 { 0 } else { fee / weight };
+		(rate, package)
+	}
+}
+
+/// Outcome of attempting to add a transaction to the pool, so callers can
+/// tell the difference between "in the pool now", "in the pool now instead
+/// of something else" and "not in the pool", rather than just a bare `()`.
+#[derive(Clone, Debug)]
+pub enum PoolAddResult {
+	/// Accepted into the pool as a new entry.
+	Accepted,
+	/// Accepted, evicting the given lower-scoring entry from the same
+	/// source to make room under that source's per-source cap.
+	Replaced(Box<Transaction>),
+	/// Rejected: the entry's score wasn't high enough to earn a slot under
+	/// general pool pressure. Losing against this source's own existing
+	/// entries is reported as `RejectedSourceCapExceeded` instead.
+	RejectedLowScore(u64),
+	/// Rejected: this source already holds its maximum allowed share of the
+	/// pool and has nothing low-scored enough of its own to evict.
+	RejectedSourceCapExceeded,
+	/// Otherwise valid, but one of its time-relative conditions (absolute
+	/// kernel lock height, NRD relative lock-height kernel) is not yet
+	/// satisfied at the current tip. Parked rather than rejected; see
+	/// `TransactionPool::reconcile_block`.
+	Pending,
+}
+
+/// Placeholder: the configuration for "Dandelion". Controls embargo timer,
+/// stem probability etc. Kept here as it lives alongside the pool config in
+/// the node configuration file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DandelionConfig {
+	/// Default embargo timer in secs.
+	pub embargo_secs: Option<u64>,
+	/// Dandelion aggregation period in secs.
+	pub aggregation_secs: Option<u64>,
+	/// Dandelion stem probability (stem 90% of the time, fluff 10%).
+	pub stem_probability: Option<usize>,
+	/// Default fallback is always stem.
+	pub always_stem_our_txs: Option<bool>,
+}
+
+/// Transaction pool configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolConfig {
+	/// Base fee rate (in nanogrin per gram of weight) below which a
+	/// transaction will not be accepted into the pool.
+	#[serde(default = "default_accept_fee_base")]
+	pub accept_fee_base: u64,
+
+	/// Nominal capacity of the txpool (and of `pending`) in transactions,
+	/// used as the base for the per-source cap (`max_share_per_source`).
+	/// The entry count itself is not bounded by it; `max_pool_weight` is
+	/// what bounds the pool.
+	#[serde(default = "default_max_pool_size")]
+	pub max_pool_size: usize,
+
+	/// As `max_pool_size`, but for the stempool.
+	#[serde(default = "default_max_stempool_size")]
+	pub max_stempool_size: usize,
+
+	/// Maximum total weight of transactions that can get selected to build
+	/// a block from. Not consulted by `prepare_mineable_transactions`, which
+	/// always selects up to `global::max_block_weight()`.
+	#[serde(default = "default_mineable_max_weight")]
+	pub mineable_max_weight: u64,
+
+	/// Maximum total weight the txpool is allowed to grow to. Once reached,
+	/// an incoming tx is only admitted if its (ancestor) fee rate beats
+	/// `Pool::min_fee_rate()`, and the lowest-scoring packages already in
+	/// the pool are evicted to make room for it.
+	#[serde(default = "default_max_pool_weight")]
+	pub max_pool_weight: u64,
+
+	/// As `max_pool_weight`, but for the stempool.
+	#[serde(default = "default_max_pool_weight")]
+	pub max_stempool_weight: u64,
+
+	/// Maximum fraction (0.0-1.0) of the pool that a single `TxSource` is
+	/// allowed to occupy, used to stop one peer or relay from crowding out
+	/// everyone else. Checked by entry count, not weight.
+	#[serde(default = "default_max_share_per_source")]
+	pub max_share_per_source: f32,
+
+	/// How much to penalize a source (see `Pool::penalize`) each time one of
+	/// its transactions fails verification in `add_to_pool`, or is dropped
+	/// from the pool because a new block double-spends its inputs (see
+	/// `Pool::reconcile`). A tx reorged back out of a block it was mined in
+	/// earns no strike. Penalized sources are scored lower, so their
+	/// entries are the first evicted once the pool is under pressure.
+	#[serde(default = "default_penalty_per_strike")]
+	pub penalty_per_strike: u32,
+}
+
+impl Default for PoolConfig {
+	fn default() -> PoolConfig {
+		PoolConfig {
+			accept_fee_base: default_accept_fee_base(),
+			max_pool_size: default_max_pool_size(),
+			max_stempool_size: default_max_stempool_size(),
+			mineable_max_weight: default_mineable_max_weight(),
+			max_pool_weight: default_max_pool_weight(),
+			max_stempool_weight: default_max_pool_weight(),
+			max_share_per_source: default_max_share_per_source(),
+			penalty_per_strike: default_penalty_per_strike(),
+		}
+	}
+}
+
+fn default_accept_fee_base() -> u64 {
+	1
+}
+fn default_max_pool_size() -> usize {
+	50_000
+}
+fn default_max_stempool_size() -> usize {
+	50_000
+}
+fn default_mineable_max_weight() -> u64 {
+	core::global::max_block_weight() as u64
+}
+fn default_max_pool_weight() -> u64 {
+	// A generous multiple of a single block's worth of weight, so the pool
+	// can comfortably hold several blocks' backlog before anything needs to
+	// be evicted.
+	50 * core::global::max_block_weight() as u64
+}
+fn default_max_share_per_source() -> f32 {
+	0.20
+}
+fn default_penalty_per_strike() -> u32 {
+	1
+}
+
+/// Possible errors when interacting with the transaction pool.
+#[derive(Debug, Fail)]
+pub enum PoolError {
+	/// An invalid pool entry caused by underlying tx validation error
+	#[fail(display = "Invalid Tx {}", _0)]
+	InvalidTx(self::core::core::transaction::Error),
+	/// Attempt to add a transaction to the pool with lock_height
+	/// greater than height of current block
+	#[fail(display = "Immature Transaction")]
+	ImmatureTransaction,
+	/// Attempt to spend a coinbase output before it has sufficiently matured.
+	#[fail(display = "Immature Coinbase")]
+	ImmatureCoinbase,
+	/// Problem propagating a stem tx to the next Dandelion relay node.
+	#[fail(display = "Dandelion Error")]
+	DandelionError,
+	/// Transaction pool is over capacity, can't accept more transactions
+	#[fail(display = "Over Capacity")]
+	OverCapacity,
+	/// Transaction fee is too low given its weight
+	#[fail(display = "Low Fee Transaction {}", _0)]
+	LowFeeTransaction(u64),
+	/// Attempt to add a duplicate tx to the pool.
+	#[fail(display = "Duplicate Tx")]
+	DuplicateTx,
+	/// Attempt to add a tx to the pool that conflicts with an existing tx.
+	#[fail(display = "Double Spend")]
+	DoubleSpend,
+	/// Tx pool size is too large
+	#[fail(display = "Pool Full")]
+	TooManyTx,
+	/// Tx weight is too large
+	#[fail(display = "Pool Full (weight)")]
+	TooHeavyTx,
+	/// This source already occupies its maximum allowed share of the pool.
+	#[fail(display = "Source Cap Exceeded {}", _0)]
+	SourceCapExceeded(String),
+	/// Failed to verify a Merkle proof during validation
+	#[fail(display = "Other Pool Error {}", _0)]
+	Other(String),
+}
+
+impl From<self::core::core::transaction::Error> for PoolError {
+	fn from(e: self::core::core::transaction::Error) -> PoolError {
+		PoolError::InvalidTx(e)
+	}
+}
+
+/// Interface that the pool requires from a blockchain implementing it.
+pub trait BlockChain: Sync + Send {
+	/// Get the header at the head of the most work chain.
+	fn chain_head(&self) -> Result<BlockHeader, PoolError>;
+
+	/// Get a block header by hash.
+	fn get_block_header(&self, hash: &Hash) -> Result<BlockHeader, PoolError>;
+
+	/// Validate a transaction against the current UTXO set.
+	fn validate_tx(&self, tx: &Transaction) -> Result<(), PoolError>;
+
+	/// Verify any coinbase outputs being spent by the transaction have
+	/// matured sufficiently.
+	fn verify_coinbase_maturity(&self, tx: &Transaction) -> Result<(), PoolError>;
+
+	/// Whether every time-relative condition on `tx` - absolute kernel lock
+	/// heights, and NRD relative lock-height kernels (elapsed height since
+	/// the kernel they reference was mined) - is satisfied as of `height`.
+	/// `Ok(false)` means `tx` is otherwise well-formed but not yet
+	/// spendable, not that it is invalid; callers should park it rather
+	/// than reject it outright.
+	fn is_tx_time_valid(&self, tx: &Transaction, height: u64) -> Result<bool, PoolError>;
+}
+
+/// Bridge between the transaction pool and the rest of the node, used to
+/// push newly accepted transactions out to the network.
+pub trait PoolAdapter: Send + Sync {
+	/// The transaction pool has accepted this transaction as valid.
+	fn tx_accepted(&self, entry: &PoolEntry);
+
+	/// The stem transaction pool has accepted this transaction as valid and
+	/// it should be stemmed to a single Dandelion relay.
+	fn stem_tx_accepted(&self, entry: &PoolEntry) -> Result<(), PoolError>;
+}
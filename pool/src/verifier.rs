@@ -0,0 +1,73 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The context-free half of pool verification: rangeproofs, kernel
+//! signatures and weight never change once a transaction has passed them,
+//! so their result is cached per tx hash and never recomputed. The other
+//! half - time-relative conditions that depend on the current tip - lives
+//! on `BlockChain::is_tx_time_valid` instead, since only that half needs
+//! re-checking as the chain grows. `TransactionPool` prunes a hash back out
+//! once its tx has left every pool for good, so the cache stays bounded by
+//! what's actually pooled rather than growing for the life of the node.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use self::core::core::hash::{Hash, Hashed};
+use self::core::core::verifier_cache::VerifierCache;
+use self::core::core::Transaction;
+use crate::core;
+use crate::types::PoolError;
+use crate::util::RwLock;
+
+/// Tracks which tx hashes have already passed context-free verification, so
+/// `add_to_pool` and `reconcile_block` never repeat that work for a
+/// transaction already proven well-formed.
+#[derive(Default)]
+pub struct ContextFreeVerifier {
+	verified: HashSet<Hash>,
+}
+
+impl ContextFreeVerifier {
+	/// Construct an empty verifier with nothing cached yet.
+	pub fn new() -> ContextFreeVerifier {
+		ContextFreeVerifier::default()
+	}
+
+	/// Verify `tx` is context-free valid (rangeproofs, kernel signatures,
+	/// weight), skipping the check entirely if this exact tx has already
+	/// passed it.
+	pub fn verify(
+		&mut self,
+		tx: &Transaction,
+		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	) -> Result<(), PoolError> {
+		let hash = tx.hash();
+		if self.verified.contains(&hash) {
+			return Ok(());
+		}
+		tx.validate(verifier_cache).map_err(PoolError::from)?;
+		self.verified.insert(hash);
+		Ok(())
+	}
+
+	/// Stop caching `hash` as context-free valid. Called once its tx has left
+	/// every pool for good - mined, evicted, reorged back out, or never
+	/// admitted anywhere in the first place - so the set doesn't grow without
+	/// bound over the life of a running node. Safe to call speculatively: if
+	/// the tx is resubmitted later it is simply re-verified.
+	pub fn forget(&mut self, hash: &Hash) {
+		self.verified.remove(hash);
+	}
+}
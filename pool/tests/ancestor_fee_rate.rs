@@ -0,0 +1,165 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test coverage for ancestor-aware (child-pays-for-parent) selection in
+//! `prepare_mineable_transactions`.
+
+pub mod common;
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::global;
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::sync::Arc;
+
+// A cheap parent with a high-fee child should still be selected together
+// (and the child never split away from its parent) when their combined
+// package fits the block, even though the parent's own fee_rate is the
+// lowest in the pool.
+#[test]
+fn test_cheap_parent_with_paying_child_is_selected_together() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.ancestor_fee_rate_fits";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 3, &keychain);
+
+	let header_1 = chain.get_header_by_height(1).unwrap();
+
+	// Provides us with an output to build a cheap parent from.
+	let initial_tx =
+		test_transaction_spending_coinbase(&keychain, &header_1, vec![500_100]);
+	add_block(&chain, &[initial_tx], &keychain);
+
+	let header = chain.head_header().unwrap();
+
+	// Parent: barely above dust, fee_rate 2.
+	let parent = test_transaction(&keychain, vec![500_100], vec![400_000, 100_000]);
+
+	// Child spends the parent's 400_000 output and pays a much higher fee,
+	// fee_rate 195 on its own - the highest individual rate in the pool.
+	let child = test_transaction(&keychain, vec![400_000], vec![200_000, 191_000]);
+
+	pool.add_to_pool(test_source(), parent.clone(), false, &header)
+		.unwrap();
+	pool.add_to_pool(test_source(), child.clone(), false, &header)
+		.unwrap();
+
+	assert_eq!(pool.total_size(), 2);
+
+	let txs = pool.prepare_mineable_transactions().unwrap();
+
+	// Both the cheap parent and its paying child get selected, with the
+	// parent ahead of the child.
+	assert_eq!(txs.len(), 2);
+	assert_eq!(txs[0].hash(), parent.hash());
+	assert_eq!(txs[1].hash(), child.hash());
+
+	clean_output_dir(db_root.into());
+}
+
+// Same fixture as `block_max_weight::test_block_building_max_weight`, with
+// one more transaction added: a high-fee child of the 40-rate tx. Even
+// though the child's own fee_rate (195) is the second highest in the pool,
+// its ancestor package rate (140, dragged down by its cheap parent) isn't
+// enough to displace the other packages once the block is full, so the
+// whole package is left out together - never split.
+#[test]
+fn test_high_fee_child_of_low_fee_parent_not_split_when_pool_is_full() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.ancestor_fee_rate_full";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 3, &keychain);
+
+	let header_1 = chain.get_header_by_height(1).unwrap();
+
+	let initial_tx = test_transaction_spending_coinbase(
+		&keychain,
+		&header_1,
+		vec![100_000, 200_000, 300_000, 1_000_000],
+	);
+	add_block(&chain, &[initial_tx], &keychain);
+
+	let header = chain.head_header().unwrap();
+
+	let txs = vec![
+		test_transaction(
+			&keychain,
+			vec![1_000_000],
+			vec![390_000, 130_000, 120_000, 110_000],
+		),
+		test_transaction(&keychain, vec![100_000], vec![90_000, 1_000]),
+		test_transaction(&keychain, vec![90_000], vec![80_000, 2_000]),
+		// The 40-rate parent. Its output is spent by `child` below.
+		test_transaction(&keychain, vec![200_000], vec![199_000]),
+		test_transaction(&keychain, vec![300_000], vec![290_000, 3_000]),
+		test_transaction(&keychain, vec![290_000], vec![280_000, 4_000]),
+		// Child of the 40-rate tx, fee_rate 195 on its own. Its output
+		// values are not used by any other tx here, so its outputs don't
+		// collide with the 100_000 and 90_000 outputs above.
+		test_transaction(&keychain, vec![199_000], vec![150_000, 40_000]),
+	];
+
+	for tx in txs {
+		pool.add_to_pool(test_source(), tx, false, &header).unwrap();
+	}
+
+	assert_eq!(pool.total_size(), 7);
+
+	let mineable = pool.prepare_mineable_transactions().unwrap();
+
+	// Same packages as the plain max-weight test - the low-fee parent and
+	// its high-fee child are excluded as a pair, not just the parent.
+	assert_eq!(
+		mineable.iter().map(|x| x.fee()).collect::<Vec<_>>(),
+		[250_000, 9_000, 8_000, 7_000]
+	);
+
+	clean_output_dir(db_root.into());
+}
@@ -0,0 +1,198 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test coverage for the bounded pool: dynamic minimum fee rate and
+//! lowest-package eviction.
+
+pub mod common;
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::global;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::types::{PoolAddResult, TxSource};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+fn source(identifier: &str) -> TxSource {
+	TxSource {
+		debug_name: "test".to_string(),
+		identifier: identifier.to_string(),
+	}
+}
+
+// Once the pool is at its weight bound, a cheap incoming tx is rejected
+// outright and a pricier one displaces the cheapest package to make room -
+// without ever leaving a child behind with its parent evicted out from
+// under it.
+#[test]
+fn test_bounded_pool_evicts_lowest_package_and_rejects_too_cheap() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.bounded_pool";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+
+	let initial_tx = test_transaction_spending_coinbase(
+		&keychain,
+		&header_1,
+		vec![500_100, 600_000, 700_000],
+	);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+
+	// Cheap parent and its high-fee child - together they are the
+	// lowest-scoring package in the pool and must go (or stay) together.
+	let parent = test_transaction(&keychain, vec![500_100], vec![400_000, 100_000]);
+	let child = test_transaction(&keychain, vec![400_000], vec![200_000, 191_000]);
+
+	// A mid-fee independent tx, fee_rate 173 - above the parent+child
+	// package rate of 98.
+	let mid = test_transaction(&keychain, vec![600_000], vec![590_000, 2_000]);
+
+	pool.add_to_pool(test_source(), parent.clone(), false, &header)
+		.unwrap();
+	pool.add_to_pool(test_source(), child.clone(), false, &header)
+		.unwrap();
+	pool.add_to_pool(test_source(), mid.clone(), false, &header)
+		.unwrap();
+
+	// Bound the pool so only two of these three transactions' weight fits,
+	// forcing an eviction decision on the next add.
+	let weight = parent.weight() as u64 + child.weight() as u64 + mid.weight() as u64;
+	pool.txpool.max_weight = weight - 1;
+
+	// A tx with a lower fee rate than everything already in the pool should
+	// be turned away outright rather than displacing anything: fee_rate 1,
+	// below even the cheap parent's 2.
+	let too_cheap = test_transaction(&keychain, vec![700_000], vec![699_975]);
+	let result = pool
+		.add_to_pool(test_source(), too_cheap, false, &header)
+		.unwrap();
+	assert!(matches!(result, PoolAddResult::RejectedLowScore(_)));
+
+	// A high-fee independent tx should be let in, evicting the cheap
+	// parent+child package as a whole rather than splitting them.
+	let rich = test_transaction(&keychain, vec![700_000], vec![650_000]);
+	pool.add_to_pool(test_source(), rich.clone(), false, &header)
+		.unwrap();
+
+	let remaining: Vec<_> = pool
+		.txpool
+		.all_transactions()
+		.iter()
+		.map(|tx| tx.hash())
+		.collect();
+	assert!(remaining.contains(&mid.hash()));
+	assert!(remaining.contains(&rich.hash()));
+	assert!(!remaining.contains(&parent.hash()));
+	assert!(!remaining.contains(&child.hash()));
+
+	clean_output_dir(db_root.into());
+}
+
+// `evict_to_fit` is the actual anti-spam enforcement point - called on
+// every insertion once the pool is at its weight bound - so a penalized
+// source's entries must lose there too, not just under the narrower
+// per-source cap. Here the penalized entry has a slightly *better* raw fee
+// rate than the unpenalized one, so raw rate alone would evict the wrong
+// one.
+#[test]
+fn test_evict_to_fit_prefers_penalized_package_over_better_raw_rate() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.bounded_pool_penalize";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+
+	let initial_tx = test_transaction_spending_coinbase(
+		&keychain,
+		&header_1,
+		vec![200_000, 300_000, 400_000],
+	);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+
+	// fee_rate 44 - slightly better than `worse_raw_rate` below.
+	let penalized = test_transaction(&keychain, vec![200_000], vec![198_900]);
+	// fee_rate 40.
+	let worse_raw_rate = test_transaction(&keychain, vec![300_000], vec![299_000]);
+	// fee_rate 200 - always kept, never the eviction target.
+	let safe = test_transaction(&keychain, vec![400_000], vec![395_000]);
+
+	let penalized_source = source("peer-a");
+
+	pool.add_to_pool(penalized_source.clone(), penalized.clone(), false, &header)
+		.unwrap();
+	pool.add_to_pool(source("peer-b"), worse_raw_rate.clone(), false, &header)
+		.unwrap();
+	pool.add_to_pool(source("peer-c"), safe.clone(), false, &header)
+		.unwrap();
+
+	// Strike `penalized`'s source heavily enough that its effective rate
+	// (44 / 3 = 14) drops well below `worse_raw_rate`'s (40).
+	pool.txpool.penalize(&penalized_source, 2);
+
+	// Only room for one of `penalized`/`worse_raw_rate` alongside `safe`.
+	pool.txpool.max_weight = safe.weight() as u64 + worse_raw_rate.weight() as u64;
+	pool.txpool.evict_to_fit();
+
+	let remaining: Vec<_> = pool
+		.txpool
+		.all_transactions()
+		.iter()
+		.map(|tx| tx.hash())
+		.collect();
+	assert!(remaining.contains(&safe.hash()));
+	assert!(remaining.contains(&worse_raw_rate.hash()));
+	assert!(!remaining.contains(&penalized.hash()));
+
+	clean_output_dir(db_root.into());
+}
@@ -0,0 +1,182 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test coverage for per-`TxSource` pool limits and penalization.
+
+pub mod common;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::global;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::types::{PoolAddResult, TxSource};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+fn source(identifier: &str) -> TxSource {
+	TxSource {
+		debug_name: "test".to_string(),
+		identifier: identifier.to_string(),
+	}
+}
+
+// A source that keeps pushing new txs in once it holds its maximum share of
+// the pool only displaces its own lowest-fee entry, and only if the new tx
+// actually outbids it. It never touches another source's entries.
+#[test]
+fn test_source_cap_replaces_own_lowest_fee_entry() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.source_limits_cap";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	// Our test pool's configured capacity is small enough (see `common`)
+	// that a single source's `max_share_per_source` allowance is just one
+	// entry, so the second tx from the same source must outbid the first.
+	pool.config.max_share_per_source = 0.01;
+
+	add_some_blocks(&chain, 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+
+	let initial_tx = test_transaction_spending_coinbase(
+		&keychain,
+		&header_1,
+		vec![200_000, 200_100],
+	);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+
+	let cheap = test_transaction(&keychain, vec![200_000], vec![199_000]);
+	let expensive = test_transaction(&keychain, vec![200_100], vec![195_000]);
+
+	let spammy = source("peer-a");
+
+	let result = pool
+		.add_to_pool(spammy.clone(), cheap.clone(), false, &header)
+		.unwrap();
+	assert!(matches!(result, PoolAddResult::Accepted));
+	assert_eq!(pool.total_size(), 1);
+
+	// Same source, higher fee: should evict `cheap` and take its place.
+	let result = pool
+		.add_to_pool(spammy.clone(), expensive.clone(), false, &header)
+		.unwrap();
+	assert!(matches!(result, PoolAddResult::Replaced(_)));
+	assert_eq!(pool.total_size(), 1);
+	assert_eq!(pool.txpool.all_transactions()[0].fee(), expensive.fee());
+
+	clean_output_dir(db_root.into());
+}
+
+// `lowest_scoring_index_for_source` picks its target by penalty-adjusted
+// ancestor package rate, not by each entry's own isolated fee rate - so the
+// candidate it's compared against under the per-source cap must be scored
+// the same way, or a high-fee child of a source's own cheap in-pool parent
+// can be wrongly turned away even though it clears that parent's package
+// rate handily.
+#[test]
+fn test_source_cap_replacement_uses_penalized_package_rate() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.source_limits_penalize";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	// As above, capped to a single slot per source.
+	pool.config.max_share_per_source = 0.01;
+
+	add_some_blocks(&chain, 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+
+	let initial_tx =
+		test_transaction_spending_coinbase(&keychain, &header_1, vec![200_000, 300_000]);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+
+	let peer_a = source("peer-a");
+
+	// A cheap parent from a different source - fee_rate 40 on its own.
+	let parent = test_transaction(&keychain, vec![200_000], vec![199_000]);
+	pool.add_to_pool(test_source(), parent.clone(), false, &header)
+		.unwrap();
+
+	// peer-a's only entry: spends the parent above, fee_rate 195 in
+	// isolation, but its *package* (parent + this tx) rate is only 140 -
+	// the metric `lowest_scoring_index_for_source` actually ranks it by.
+	let laden = test_transaction(&keychain, vec![199_000], vec![100_000, 90_000]);
+	let result = pool
+		.add_to_pool(peer_a.clone(), laden.clone(), false, &header)
+		.unwrap();
+	assert!(matches!(result, PoolAddResult::Accepted));
+
+	// peer-a picks up a strike, e.g. from an earlier tx that failed
+	// verification - this should not by itself change the outcome below
+	// (it scales both sides of the comparison equally), but must still be
+	// reflected consistently rather than silently ignored by whichever
+	// side of the comparison forgets to apply it.
+	pool.txpool.penalize(&peer_a, 1);
+	assert_eq!(pool.txpool.penalty(&peer_a), 1);
+
+	// A new peer-a tx, independent of `parent`/`laden`, with fee_rate 150:
+	// comfortably above `laden`'s package rate of 140, but below `laden`'s
+	// own isolated rate of 195.
+	let candidate = test_transaction(&keychain, vec![300_000], vec![290_000, 3_100]);
+
+	let result = pool
+		.add_to_pool(peer_a.clone(), candidate.clone(), false, &header)
+		.unwrap();
+	assert!(matches!(result, PoolAddResult::Replaced(_)));
+
+	let remaining: Vec<_> = pool
+		.txpool
+		.all_transactions()
+		.iter()
+		.map(|tx| tx.fee())
+		.collect();
+	assert!(!remaining.contains(&laden.fee()));
+	assert!(remaining.contains(&parent.fee()));
+	assert!(remaining.contains(&candidate.fee()));
+
+	clean_output_dir(db_root.into());
+}
@@ -0,0 +1,93 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test coverage for the context-free / time-relative verification split: a
+//! tx whose absolute lock height hasn't been reached yet is parked rather
+//! than rejected, and is promoted into the txpool on its own once the tip
+//! reaches it.
+
+pub mod common;
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::global;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::types::PoolAddResult;
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+#[test]
+fn test_immature_tx_is_parked_then_promoted_on_tip_advance() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.time_relative";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header_1, vec![500_000]);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+	let lock_height = header.height + 2;
+
+	// Not yet spendable: its absolute lock height is ahead of the tip.
+	let locked_tx =
+		test_transaction_with_lock_height(&keychain, vec![500_000], vec![490_000], lock_height);
+
+	let result = pool
+		.add_to_pool(test_source(), locked_tx.clone(), false, &header)
+		.unwrap();
+	assert!(matches!(result, PoolAddResult::Pending));
+	assert_eq!(pool.total_size(), 0);
+	assert_eq!(pool.pending_size(), 1);
+
+	// Mine up to the lock height. The tx is never resubmitted - reconcile_block
+	// re-checks `pending` against the new tip and promotes it on its own.
+	while chain.head_header().unwrap().height < lock_height {
+		add_block(&chain, &[], &keychain);
+	}
+	let head_hash = chain.head_header().unwrap().hash();
+	let block = chain.get_block(&head_hash).unwrap();
+	pool.reconcile_block(&block).unwrap();
+
+	assert_eq!(pool.pending_size(), 0);
+	let mineable: Vec<_> = pool
+		.txpool
+		.all_transactions()
+		.iter()
+		.map(|tx| tx.hash())
+		.collect();
+	assert!(mineable.contains(&locked_tx.hash()));
+
+	clean_output_dir(db_root.into());
+}